@@ -0,0 +1,87 @@
+use regex::Regex;
+
+/// A single dialect-repair rule: a pattern matching a foreign-SQL idiom and the
+/// DuckDB replacement it is rewritten to. Replacements use the usual `${n}`
+/// capture-group references.
+pub struct DialectRule {
+    pattern: Regex,
+    replacement: String,
+}
+
+/// Deterministic post-processing pass that rewrites a fixed set of
+/// non-DuckDB idioms the model occasionally emits into valid DuckDB syntax,
+/// in the spirit of a SQL transpiler's dialect mapping. Callers can grow the
+/// rule table with [`DialectRewriter::push_rule`].
+pub struct DialectRewriter {
+    rules: Vec<DialectRule>,
+}
+
+impl DialectRewriter {
+    /// The default set of DuckDB dialect-repair rules.
+    pub fn duckdb() -> Self {
+        let rules = [
+            // DATEADD(unit, n, x) -> CAST(x AS DATE) + INTERVAL n unit
+            (
+                r"(?i)\bDATEADD\s*\(\s*([^,()]+?)\s*,\s*([^,()]+?)\s*,\s*([^,()]+?)\s*\)",
+                "CAST(${3} AS DATE) + INTERVAL ${2} ${1}",
+            ),
+            // DATE_ADD(x, INTERVAL n unit) -> CAST(x AS DATE) + INTERVAL n unit
+            (
+                r"(?i)\bDATE_ADD\s*\(\s*([^,()]+?)\s*,\s*INTERVAL\s+([^,()]+?)\s+([A-Za-z]+)\s*\)",
+                "CAST(${1} AS DATE) + INTERVAL ${2} ${3}",
+            ),
+            // JSON_EXTRACT_STRING(col, '$.a') -> col->>'$.a' (scalar form)
+            (
+                r"(?i)\bJSON_EXTRACT_STRING\s*\(\s*([^,()]+?)\s*,\s*('[^']*')\s*\)",
+                "${1}->>${2}",
+            ),
+            // JSON_EXTRACT(col, '$.a') -> col->'$.a'
+            (
+                r"(?i)\bJSON_EXTRACT\s*\(\s*([^,()]+?)\s*,\s*('[^']*')\s*\)",
+                "${1}->${2}",
+            ),
+            // ARRAY_SORT(x, comparator) -> ARRAY_SORT(x) (comparator unsupported)
+            (
+                r"(?i)\bARRAY_SORT\s*\(\s*([^,()]+?)\s*,\s*[^()]+?\s*\)",
+                "ARRAY_SORT(${1})",
+            ),
+            // NVL / IFNULL -> COALESCE
+            (r"(?i)\b(?:NVL|IFNULL)\b", "COALESCE"),
+            // LOCATE(substring, string) -> strpos(string, substring) (normalize
+            // foreign argument order to DuckDB's strpos(string, search_string))
+            (
+                r"(?i)\bLOCATE\s*\(\s*([^,()]+?)\s*,\s*([^,()]+?)\s*\)",
+                "strpos(${2}, ${1})",
+            ),
+        ];
+        Self {
+            rules: rules
+                .into_iter()
+                .map(|(pattern, replacement)| DialectRule {
+                    pattern: Regex::new(pattern).expect("invalid dialect rule regex"),
+                    replacement: replacement.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Append a rule so the table can grow without editing the defaults.
+    pub fn push_rule(&mut self, pattern: Regex, replacement: impl Into<String>) {
+        self.rules.push(DialectRule {
+            pattern,
+            replacement: replacement.into(),
+        });
+    }
+
+    /// Apply every rule in order, returning the rewritten SQL.
+    pub fn apply(&self, sql: &str) -> String {
+        let mut output = sql.to_string();
+        for rule in &self.rules {
+            output = rule
+                .pattern
+                .replace_all(&output, rule.replacement.as_str())
+                .into_owned();
+        }
+        output
+    }
+}
@@ -1,16 +1,269 @@
-use sql_code_generator::SqlCodeGenerator;
+use core::pin::Pin;
+use std::cell::RefCell;
 
+use sql_code_generator::{
+    ColumnInfo, GenerationConfig, SchemaProvider, SqlCodeGenerator, SqlValidator, TokenSink,
+};
+
+mod dialect;
+mod retrieval;
 mod sql_code_generator;
 
 pub fn create_sql_code_generator() -> Box<SqlCodeGenerator> {
-    Box::new(SqlCodeGenerator::new().expect("Failed to construct"))
+    Box::new(SqlCodeGenerator::new(GenerationConfig::default()).expect("Failed to construct"))
+}
+
+impl From<ffi::GenerationConfig> for GenerationConfig {
+    fn from(c: ffi::GenerationConfig) -> Self {
+        GenerationConfig {
+            temperature: c.temperature,
+            top_p: c.top_p,
+            top_k: c.top_k,
+            seed: c.seed,
+            repeat_penalty: c.repeat_penalty,
+            repeat_last_n: c.repeat_last_n,
+            max_tokens: c.max_tokens,
+        }
+    }
+}
+
+/// Construct a generator with caller-supplied decoding parameters.
+pub fn create_sql_code_generator_with_config(config: ffi::GenerationConfig) -> Box<SqlCodeGenerator> {
+    Box::new(SqlCodeGenerator::new(config.into()).expect("Failed to construct"))
+}
+
+/// Generate `m` candidates against `conn` and return them ranked best-first,
+/// each flagged with whether it validated. Exposed over the bridge for the
+/// host's "try several, keep the one that works" path.
+fn generate_n_best(
+    generator: &mut SqlCodeGenerator,
+    prompt: &str,
+    table_schemas: &str,
+    conn: Pin<&mut ffi::DuckDBConnection>,
+    m: usize,
+) -> Result<Vec<ffi::SqlCandidate>, anyhow::Error> {
+    let validator = BridgeValidator {
+        conn: RefCell::new(conn),
+    };
+    let candidates = generator.generate_n_best(prompt, table_schemas, &validator, m)?;
+    Ok(candidates
+        .into_iter()
+        .map(|c| ffi::SqlCandidate {
+            sql: c.sql,
+            score: c.score,
+            validated: c.validated,
+        })
+        .collect())
+}
+
+/// Validator that dry-runs a candidate statement against a live DuckDB
+/// connection by calling back into the host extension over the bridge.
+struct BridgeValidator<'a> {
+    conn: RefCell<Pin<&'a mut ffi::DuckDBConnection>>,
+}
+
+impl SqlValidator for BridgeValidator<'_> {
+    fn validate(&self, sql: &str) -> Result<(), String> {
+        ffi::validate_sql(self.conn.borrow_mut().as_mut(), sql).map_err(|e| e.what().to_string())
+    }
+}
+
+/// Generate SQL and repair it against `conn`, retrying up to `max_attempts`
+/// times. Exposed over the bridge so the host can hand in its own connection.
+fn generate_validated(
+    generator: &mut SqlCodeGenerator,
+    prompt: &str,
+    table_schemas: &str,
+    conn: Pin<&mut ffi::DuckDBConnection>,
+    max_attempts: usize,
+) -> Result<ffi::ValidatedSql, anyhow::Error> {
+    let validator = BridgeValidator {
+        conn: RefCell::new(conn),
+    };
+    let result = generator.generate_validated(prompt, table_schemas, &validator, max_attempts)?;
+    Ok(ffi::ValidatedSql {
+        sql: result.sql,
+        validated: result.validated,
+    })
+}
+
+/// Schema provider that builds the prompt's schema context by introspecting the
+/// live catalog through the host extension over the bridge.
+struct BridgeSchemaProvider<'a> {
+    conn: RefCell<Pin<&'a mut ffi::DuckDBConnection>>,
+}
+
+impl SchemaProvider for BridgeSchemaProvider<'_> {
+    fn columns(&self, table_filter: &str) -> Result<Vec<ColumnInfo>, String> {
+        let rows = ffi::introspect_columns(self.conn.borrow_mut().as_mut(), table_filter)
+            .map_err(|e| e.what().to_string())?;
+        Ok(rows
+            .into_iter()
+            .map(|c| ColumnInfo {
+                table_name: c.table_name,
+                column_name: c.column_name,
+                data_type: c.data_type,
+                is_primary_key: c.is_primary_key,
+                references: c.references,
+            })
+            .collect())
+    }
+}
+
+/// Generate SQL, deriving the schema context from `conn`'s catalog instead of a
+/// caller-supplied schema string. `table_names` is a comma-separated restriction
+/// list, or empty for every attached table.
+fn generate_introspected(
+    generator: &mut SqlCodeGenerator,
+    prompt: &str,
+    conn: Pin<&mut ffi::DuckDBConnection>,
+    table_names: &str,
+) -> Result<String, anyhow::Error> {
+    let provider = BridgeSchemaProvider {
+        conn: RefCell::new(conn),
+    };
+    let filter: Vec<String> = if table_names.is_empty() {
+        Vec::new()
+    } else {
+        table_names.split(',').map(|s| s.trim().to_string()).collect()
+    };
+    generator.generate_introspected(prompt, &provider, &filter)
+}
+
+/// Token sink that forwards each decoded fragment to the host extension over
+/// the bridge and polls its cancellation flag.
+struct BridgeTokenSink<'a> {
+    sink: RefCell<Pin<&'a mut ffi::TokenSink>>,
+}
+
+impl TokenSink for BridgeTokenSink<'_> {
+    fn on_token(&mut self, fragment: &str) {
+        self.sink.borrow_mut().as_mut().on_token(fragment);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.sink.borrow().is_cancelled()
+    }
+}
+
+/// Generate SQL, streaming each fragment to `sink` as it is decoded. Exposed
+/// over the bridge so the host can render partial SQL progressively.
+fn generate_streaming(
+    generator: &mut SqlCodeGenerator,
+    prompt: &str,
+    table_schemas: &str,
+    sink: Pin<&mut ffi::TokenSink>,
+) -> Result<String, anyhow::Error> {
+    let mut bridge_sink = BridgeTokenSink {
+        sink: RefCell::new(sink),
+    };
+    generator.generate_streaming(prompt, table_schemas, &mut bridge_sink)
 }
 
 #[cxx::bridge]
 mod ffi {
+    /// Result of an execute-and-repair run: the best SQL produced and whether
+    /// it passed DuckDB validation.
+    struct ValidatedSql {
+        sql: String,
+        validated: bool,
+    }
+
+    /// Decoding parameters threaded into the generator, field for field. An
+    /// all-zero struct is not the greedy default: [`GenerationConfig::default`]
+    /// uses seed 299792458, repeat_penalty 1.10, repeat_last_n 64 and
+    /// max_tokens 256.
+    struct GenerationConfig {
+        temperature: f64,
+        top_p: f64,
+        top_k: usize,
+        seed: u64,
+        repeat_penalty: f32,
+        repeat_last_n: usize,
+        max_tokens: usize,
+    }
+
+    /// One ranked N-best candidate: the SQL, its mean per-token log-probability,
+    /// and whether it validated against the connection.
+    struct SqlCandidate {
+        sql: String,
+        score: f64,
+        validated: bool,
+    }
+
+    /// One catalog column introspected from the live connection.
+    struct ColumnInfo {
+        table_name: String,
+        column_name: String,
+        data_type: String,
+        is_primary_key: bool,
+        /// `other_table(other_column)` for a foreign-key column, empty otherwise.
+        references: String,
+    }
+
     extern "Rust" {
         type SqlCodeGenerator;
         fn create_sql_code_generator() -> Box<SqlCodeGenerator>;
-        fn generate(self: &mut SqlCodeGenerator, prompt: &str, table_schemas:&str) -> Result<String>;
+        fn create_sql_code_generator_with_config(
+            config: GenerationConfig,
+        ) -> Box<SqlCodeGenerator>;
+        fn generate(self: &mut SqlCodeGenerator, prompt: &str, table_schemas: &str)
+            -> Result<String>;
+        fn set_dialect_repair(self: &mut SqlCodeGenerator, enabled: bool);
+        fn generate_validated(
+            generator: &mut SqlCodeGenerator,
+            prompt: &str,
+            table_schemas: &str,
+            conn: Pin<&mut DuckDBConnection>,
+            max_attempts: usize,
+        ) -> Result<ValidatedSql>;
+        fn generate_introspected(
+            generator: &mut SqlCodeGenerator,
+            prompt: &str,
+            conn: Pin<&mut DuckDBConnection>,
+            table_names: &str,
+        ) -> Result<String>;
+        fn generate_n_best(
+            generator: &mut SqlCodeGenerator,
+            prompt: &str,
+            table_schemas: &str,
+            conn: Pin<&mut DuckDBConnection>,
+            m: usize,
+        ) -> Result<Vec<SqlCandidate>>;
+        fn generate_streaming(
+            generator: &mut SqlCodeGenerator,
+            prompt: &str,
+            table_schemas: &str,
+            sink: Pin<&mut TokenSink>,
+        ) -> Result<String>;
+    }
+
+    unsafe extern "C++" {
+        include!("duckdb-llm-extension/validator.hpp");
+
+        /// Opaque handle to the DuckDB connection the extension is embedded in.
+        type DuckDBConnection;
+
+        /// Dry-run `sql` (via `EXPLAIN`) on `conn`; a thrown binder/parser error
+        /// surfaces as `Err` carrying the verbatim DuckDB message.
+        fn validate_sql(conn: Pin<&mut DuckDBConnection>, sql: &str) -> Result<()>;
+
+        /// Query the catalog (`information_schema.columns` / `duckdb_tables`) on
+        /// `conn`, optionally restricted to the comma-separated `table_filter`,
+        /// returning one row per column with primary-key and foreign-key hints.
+        fn introspect_columns(
+            conn: Pin<&mut DuckDBConnection>,
+            table_filter: &str,
+        ) -> Result<Vec<ColumnInfo>>;
+
+        /// Host-side sink for streamed generation. `on_token` receives each
+        /// decoded fragment; `is_cancelled` is polled each step to stop early.
+        type TokenSink;
+
+        /// Append a decoded SQL fragment to the host's incremental output.
+        fn on_token(self: Pin<&mut TokenSink>, fragment: &str);
+
+        /// Whether the host has requested generation be cancelled.
+        fn is_cancelled(self: &TokenSink) -> bool;
     }
 }
@@ -0,0 +1,115 @@
+use anyhow::Error;
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config};
+use hf_hub::{api::sync::Api, Repo, RepoType};
+use tokenizers::Tokenizer;
+
+/// Default corpus of short DuckDB documentation snippets, one per line. The
+/// corpus is embedded at build time; swapping this file and rebuilding updates
+/// the model's SQL knowledge without retraining the generator.
+const DEFAULT_CORPUS: &str = include_str!("duckdb_docs.txt");
+
+/// Number of documentation snippets spliced into the prompt by default.
+pub const DEFAULT_TOP_K: usize = 5;
+
+/// A brute-force cosine-similarity index over pre-embedded DuckDB documentation
+/// snippets. The corpus is small enough (a few thousand entries at most) that a
+/// full dot-product scan is cheaper than maintaining an ANN index.
+pub struct DocRetriever {
+    model: BertModel,
+    tokenizer: Tokenizer,
+    device: Device,
+    snippets: Vec<String>,
+    embeddings: Vec<Vec<f32>>,
+}
+
+impl DocRetriever {
+    /// Load the sentence-embedding model and embed the default documentation
+    /// corpus. Uses the same hf-hub + candle `VarBuilder` pattern as the
+    /// generator model.
+    pub fn new(device: &Device) -> Result<Self, Error> {
+        Self::with_corpus(device, DEFAULT_CORPUS)
+    }
+
+    /// Load the embedding model and embed the snippets of an arbitrary corpus
+    /// (one snippet per non-empty line).
+    pub fn with_corpus(device: &Device, corpus: &str) -> Result<Self, Error> {
+        let api = Api::new()?;
+        let repo = api.repo(Repo::with_revision(
+            "sentence-transformers/all-MiniLM-L6-v2".to_string(),
+            RepoType::Model,
+            "main".to_string(),
+        ));
+        let tokenizer = Tokenizer::from_file(repo.get("tokenizer.json")?).map_err(Error::msg)?;
+        let config: Config = serde_json::from_slice(&std::fs::read(repo.get("config.json")?)?)?;
+        let vb = unsafe {
+            VarBuilder::from_mmaped_safetensors(&[repo.get("model.safetensors")?], DType::F32, device)?
+        };
+        let model = BertModel::load(vb, &config)?;
+
+        let snippets: Vec<String> = corpus
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        let mut retriever = Self {
+            model,
+            tokenizer,
+            device: device.clone(),
+            snippets,
+            embeddings: Vec::new(),
+        };
+        retriever.embeddings = retriever
+            .snippets
+            .iter()
+            .map(|snippet| retriever.embed(snippet))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(retriever)
+    }
+
+    /// Return the `k` snippets whose embeddings are most similar to `query`,
+    /// ordered from most to least relevant.
+    pub fn top_k(&self, query: &str, k: usize) -> Result<Vec<String>, Error> {
+        if self.snippets.is_empty() {
+            return Ok(Vec::new());
+        }
+        let query_embedding = self.embed(query)?;
+        let mut scored: Vec<(f32, usize)> = self
+            .embeddings
+            .iter()
+            .enumerate()
+            .map(|(index, embedding)| (cosine_similarity(&query_embedding, embedding), index))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Ok(scored
+            .into_iter()
+            .take(k)
+            .map(|(_, index)| self.snippets[index].clone())
+            .collect())
+    }
+
+    /// Embed a single piece of text into a mean-pooled, L2-normalized vector.
+    fn embed(&self, text: &str) -> Result<Vec<f32>, Error> {
+        let encoding = self.tokenizer.encode(text, true).map_err(Error::msg)?;
+        let tokens = Tensor::new(encoding.get_ids(), &self.device)?.unsqueeze(0)?;
+        let token_type_ids = tokens.zeros_like()?;
+        let attention_mask = Tensor::new(encoding.get_attention_mask(), &self.device)?.unsqueeze(0)?;
+        let embeddings =
+            self.model
+                .forward(&tokens, &token_type_ids, Some(&attention_mask))?;
+
+        // Mean-pool over the sequence dimension and L2-normalize so that a dot
+        // product is the cosine similarity.
+        let (_batch, seq_len, _hidden) = embeddings.dims3()?;
+        let pooled = (embeddings.sum(1)? / seq_len as f64)?;
+        let normalized = pooled.broadcast_div(&pooled.sqr()?.sum_keepdim(1)?.sqrt()?)?;
+        Ok(normalized.squeeze(0)?.to_vec1::<f32>()?)
+    }
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
+}
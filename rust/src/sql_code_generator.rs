@@ -1,17 +1,21 @@
 use core::fmt;
+use std::collections::HashMap;
 
 use anyhow::Error;
 use candle_core::{DType, Device, Tensor};
 use candle_examples::token_output_stream::TokenOutputStream;
 use candle_nn::VarBuilder;
 use candle_transformers::{
-    generation::LogitsProcessor,
+    generation::{LogitsProcessor, Sampling},
     models::qwen2::{Config, ModelForCausalLM},
 };
 use chat_templates::{apply_template, Message};
 use hf_hub::{api::sync::Api, Repo, RepoType};
 use tokenizers::Tokenizer;
 
+use crate::dialect::DialectRewriter;
+use crate::retrieval::{DocRetriever, DEFAULT_TOP_K};
+
 const SYSTEM_PROMPT: &str = r#"System:
 Your task is to generate valid DuckDB SQL to answer the question that the user asks. You should only respond with a valid DuckDB SQL query.
 
@@ -41,13 +45,132 @@ Here are some DuckDB SQL syntax specifics you should be aware of:
 - DuckDB has a way to quickly get a subset of your data with `SELECT * FROM large_table USING SAMPLE 10%;`
 "#;
 
+/// Dry-run validator for a candidate SQL statement. Implemented on the host
+/// side over the `cxx::bridge` by `EXPLAIN`-ing the statement (or binding it as
+/// a prepared statement) against a live DuckDB connection; the binder/parser
+/// error message is surfaced back as the `Err` string for re-prompting.
+pub trait SqlValidator {
+    fn validate(&self, sql: &str) -> Result<(), String>;
+}
+
+/// Outcome of [`SqlCodeGenerator::generate_validated`]: the best SQL produced
+/// and whether it passed DuckDB validation.
+pub struct ValidatedSql {
+    pub sql: String,
+    pub validated: bool,
+}
+
+/// A single column of a catalog table, as introspected from the live database.
+pub struct ColumnInfo {
+    pub table_name: String,
+    pub column_name: String,
+    pub data_type: String,
+    pub is_primary_key: bool,
+    /// `other_table(other_column)` for a foreign-key column, empty otherwise.
+    pub references: String,
+}
+
+/// Catalog introspection over a live DuckDB connection. Implemented on the host
+/// side over the `cxx::bridge` by querying `information_schema.columns` /
+/// `duckdb_tables`; `table_filter` is a comma-separated list of table names to
+/// restrict to, or empty for every attached table.
+pub trait SchemaProvider {
+    fn columns(&self, table_filter: &str) -> Result<Vec<ColumnInfo>, String>;
+}
+
+/// Host-side sink for incremental decode output. Implemented over the
+/// `cxx::bridge`: `on_token` is invoked with each text fragment as
+/// [`TokenOutputStream::next_token`] yields it, and `is_cancelled` is polled
+/// each step so the host can stop generation early.
+pub trait TokenSink {
+    fn on_token(&mut self, fragment: &str);
+    fn is_cancelled(&self) -> bool;
+}
+
+/// Decoding parameters for generation. [`GenerationConfig::default`] reproduces
+/// the original greedy configuration (`temperature 0.0`, fixed seed, no
+/// nucleus/top-k truncation, `repeat_penalty 1.10` over the last 64 tokens,
+/// 256 tokens at most).
+#[derive(Clone)]
+pub struct GenerationConfig {
+    pub temperature: f64,
+    /// Nucleus sampling cutoff; `0.0` disables top-p.
+    pub top_p: f64,
+    /// Top-k truncation; `0` disables top-k.
+    pub top_k: usize,
+    pub seed: u64,
+    pub repeat_penalty: f32,
+    pub repeat_last_n: usize,
+    pub max_tokens: usize,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        Self {
+            temperature: 0.0,
+            top_p: 0.0,
+            top_k: 0,
+            seed: 299792458,
+            repeat_penalty: 1.10,
+            repeat_last_n: 64,
+            max_tokens: 256,
+        }
+    }
+}
+
+/// One ranked completion from N-best generation: the (dialect-repaired) SQL, its
+/// mean per-token log-probability used for ranking, and whether it validated.
+pub struct SqlCandidate {
+    pub sql: String,
+    pub score: f64,
+    pub validated: bool,
+}
+
 pub struct SqlCodeGenerator {
     model: ModelForCausalLM,
     device: Device,
     tokenizer: TokenOutputStream,
-    logits_processor: LogitsProcessor,
-    repeat_penalty: f32,
-    repeat_last_n: usize,
+    config: GenerationConfig,
+    retriever: DocRetriever,
+    dialect_rewriter: DialectRewriter,
+    dialect_repair_enabled: bool,
+    schema_cache: HashMap<String, String>,
+}
+
+/// Format introspected catalog columns as `CREATE TABLE` statements, one per
+/// table in first-seen order, annotating primary keys and foreign-key hints.
+fn format_schema(columns: &[ColumnInfo]) -> String {
+    let mut tables: Vec<(String, Vec<&ColumnInfo>)> = Vec::new();
+    for column in columns {
+        match tables.iter_mut().find(|(name, _)| *name == column.table_name) {
+            Some((_, cols)) => cols.push(column),
+            None => tables.push((column.table_name.clone(), vec![column])),
+        }
+    }
+
+    let mut output = String::new();
+    for (table_name, cols) in tables {
+        output.push_str(&format!("CREATE TABLE {}(", table_name));
+        let mut parts: Vec<String> = Vec::new();
+        for column in &cols {
+            let mut part = format!("{} {}", column.column_name, column.data_type);
+            if column.is_primary_key {
+                part.push_str(" PRIMARY KEY");
+            }
+            parts.push(part);
+        }
+        for column in &cols {
+            if !column.references.is_empty() {
+                parts.push(format!(
+                    "FOREIGN KEY ({}) REFERENCES {}",
+                    column.column_name, column.references
+                ));
+            }
+        }
+        output.push_str(&parts.join(", "));
+        output.push_str(");\n");
+    }
+    output
 }
 
 fn get_device() -> Result<Device, Error> {
@@ -58,7 +181,7 @@ fn get_device() -> Result<Device, Error> {
 }
 
 impl SqlCodeGenerator {
-    pub fn new() -> Result<Self, Error> {
+    pub fn new(gen_config: GenerationConfig) -> Result<Self, Error> {
         let api = Api::new()?;
         let model_id = "benjamintli/duckdb-sqlcoder-0.5B".to_string();
         let repo = api.repo(Repo::with_revision(
@@ -76,27 +199,83 @@ impl SqlCodeGenerator {
         let vb = unsafe { VarBuilder::from_mmaped_safetensors(&filenames, dtype, &device)? };
         let config: Config = serde_json::from_slice(&std::fs::read(config_file)?)?;
         let model = ModelForCausalLM::new(&config, vb)?;
-        let logits_processor = LogitsProcessor::new(299792458, Some(0.0), None);
+        let retriever = DocRetriever::new(&device)?;
         Ok(Self {
             model,
             tokenizer: TokenOutputStream::new(tokenizer),
-            logits_processor,
-            repeat_penalty: 1.10,
-            repeat_last_n: 64,
+            config: gen_config,
             device: device,
+            retriever,
+            dialect_rewriter: DialectRewriter::duckdb(),
+            dialect_repair_enabled: true,
+            schema_cache: HashMap::new(),
         })
     }
 
+    /// Build the system message for `prompt`, appending the most relevant
+    /// DuckDB documentation snippets retrieved from the embedded corpus so the
+    /// model is grounded in current syntax rather than only the static block.
+    fn system_prompt_with_docs(&self, prompt: &str) -> Result<String, Error> {
+        let snippets = self.retriever.top_k(prompt, DEFAULT_TOP_K)?;
+        if snippets.is_empty() {
+            return Ok(SYSTEM_PROMPT.to_string());
+        }
+        let mut system_prompt = String::from(SYSTEM_PROMPT);
+        system_prompt.push_str("\nRelevant DuckDB docs:\n");
+        for snippet in snippets {
+            system_prompt.push_str("- ");
+            system_prompt.push_str(&snippet);
+            system_prompt.push('\n');
+        }
+        Ok(system_prompt)
+    }
+
     pub fn generate(&mut self, prompt: &str, table_schema: &str) -> Result<String, Error> {
+        let system_prompt = self.system_prompt_with_docs(prompt)?;
+        let user_prompt = format!("{}\nSCHEMA: {}", prompt, table_schema);
+        let seed = self.config.seed;
+        Ok(self.complete(&system_prompt, &user_prompt, seed)?.0)
+    }
+
+    /// Build a [`LogitsProcessor`] from the active [`GenerationConfig`] for the
+    /// given `seed`. A non-positive temperature selects greedy argmax sampling,
+    /// matching the original behavior; otherwise top-k/top-p truncation is
+    /// applied as configured.
+    fn logits_processor(&self, seed: u64) -> LogitsProcessor {
+        if self.config.temperature <= 0.0 {
+            return LogitsProcessor::new(seed, Some(0.0), None);
+        }
+        let temperature = self.config.temperature;
+        let top_k = (self.config.top_k > 0).then_some(self.config.top_k);
+        let top_p = (self.config.top_p > 0.0).then_some(self.config.top_p);
+        let sampling = match (top_k, top_p) {
+            (None, None) => Sampling::All { temperature },
+            (Some(k), None) => Sampling::TopK { k, temperature },
+            (None, Some(p)) => Sampling::TopP { p, temperature },
+            (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
+        };
+        LogitsProcessor::from_sampling(seed, sampling)
+    }
+
+    /// Decode a single completion for the given system and user turns, sampling
+    /// with the active config seeded by `seed`. Returns the (dialect-repaired)
+    /// output and its mean per-token log-probability, used to rank N-best
+    /// candidates.
+    fn complete(
+        &mut self,
+        system_prompt: &str,
+        user_prompt: &str,
+        seed: u64,
+    ) -> Result<(String, f64), Error> {
+        let mut logits_processor = self.logits_processor(seed);
         self.tokenizer.clear();
-        let combined_prompt = format!("{}\nSCHEMA: {}", prompt, table_schema);
         let system_message = Message {
             role: "system".to_string(),
-            content: SYSTEM_PROMPT.to_string(),
+            content: system_prompt.to_string(),
         };
         let user_message = Message {
             role: "user".to_string(),
-            content: combined_prompt,
+            content: user_prompt.to_string(),
         };
         let chat_template = apply_template(
             chat_templates::ChatTemplate::ChatML,
@@ -120,25 +299,31 @@ impl SqlCodeGenerator {
             Some(token) => token,
             None => anyhow::bail!("cannot find the <|im_end|> token"),
         };
-        for index in 0..256 {
+        let mut logprob_sum = 0.0f64;
+        let mut sampled = 0usize;
+        for index in 0..self.config.max_tokens {
             let context_size = if index > 0 { 1 } else { tokens.len() };
             let start_pos = tokens.len().saturating_sub(context_size);
             let ctxt = &tokens[start_pos..];
             let input = Tensor::new(ctxt, &self.device)?.unsqueeze(0)?;
             let logits = self.model.forward(&input, start_pos)?;
             let logits = logits.squeeze(0)?.squeeze(0)?.to_dtype(DType::F32)?;
-            let logits = if self.repeat_penalty == 1. {
+            let logits = if self.config.repeat_penalty == 1. {
                 logits
             } else {
-                let start_at = tokens.len().saturating_sub(self.repeat_last_n);
+                let start_at = tokens.len().saturating_sub(self.config.repeat_last_n);
                 candle_transformers::utils::apply_repeat_penalty(
                     &logits,
-                    self.repeat_penalty,
+                    self.config.repeat_penalty,
                     &tokens[start_at..],
                 )?
             };
 
-            let next_token = self.logits_processor.sample(&logits)?;
+            let next_token = logits_processor.sample(&logits)?;
+            let probs = candle_nn::ops::softmax(&logits, 0)?;
+            let p = probs.get(next_token as usize)?.to_scalar::<f32>()? as f64;
+            logprob_sum += p.max(1e-12).ln();
+            sampled += 1;
             tokens.push(next_token);
             if next_token == eos_token || next_token == eos_token2 {
                 break;
@@ -151,6 +336,251 @@ impl SqlCodeGenerator {
             output.push_str(&rest);
         }
         self.model.clear_kv_cache();
+        if self.dialect_repair_enabled {
+            output = self.dialect_rewriter.apply(&output);
+        }
+        let score = if sampled == 0 {
+            0.0
+        } else {
+            logprob_sum / sampled as f64
+        };
+        Ok((output, score))
+    }
+
+    /// Enable or disable the deterministic dialect-repair pass applied to model
+    /// output. Enabled by default.
+    pub fn set_dialect_repair(&mut self, enabled: bool) {
+        self.dialect_repair_enabled = enabled;
+    }
+
+    /// Build the schema context by introspecting the live catalog through
+    /// `provider`, optionally restricted to `table_names`. The formatted result
+    /// is cached per restriction set so repeated calls don't re-query the
+    /// catalog; use [`SqlCodeGenerator::clear_schema_cache`] after the schema
+    /// changes.
+    pub fn schema_context(
+        &mut self,
+        provider: &dyn SchemaProvider,
+        table_names: &[String],
+    ) -> Result<String, Error> {
+        let filter = table_names.join(",");
+        if let Some(cached) = self.schema_cache.get(&filter) {
+            return Ok(cached.clone());
+        }
+        let columns = provider.columns(&filter).map_err(Error::msg)?;
+        let schema = format_schema(&columns);
+        self.schema_cache.insert(filter, schema.clone());
+        Ok(schema)
+    }
+
+    /// Drop any cached introspected schema so the next call re-queries the
+    /// catalog.
+    pub fn clear_schema_cache(&mut self) {
+        self.schema_cache.clear();
+    }
+
+    /// Generate SQL, deriving the schema context from the live catalog instead
+    /// of a caller-supplied schema string.
+    pub fn generate_introspected(
+        &mut self,
+        prompt: &str,
+        provider: &dyn SchemaProvider,
+        table_names: &[String],
+    ) -> Result<String, Error> {
+        let schema = self.schema_context(provider, table_names)?;
+        self.generate(prompt, &schema)
+    }
+
+    /// Generate SQL and validate it against a live DuckDB connection, re-prompting
+    /// the model with the verbatim error on failure. Runs the dry run (an
+    /// `EXPLAIN` of the candidate) through the `cxx::bridge` into the host
+    /// extension, retrying up to `max_attempts` times. Returns the first
+    /// statement that validates, or the last attempt with `validated == false`
+    /// if every attempt fails.
+    pub fn generate_validated(
+        &mut self,
+        prompt: &str,
+        table_schema: &str,
+        validator: &dyn SqlValidator,
+        max_attempts: usize,
+    ) -> Result<ValidatedSql, Error> {
+        let system_prompt = self.system_prompt_with_docs(prompt)?;
+        let base_user_prompt = format!("{}\nSCHEMA: {}", prompt, table_schema);
+        let attempts = max_attempts.max(1);
+        let mut user_prompt = base_user_prompt.clone();
+        let mut last = String::new();
+        let seed = self.config.seed;
+        for _ in 0..attempts {
+            let (sql, _) = self.complete(&system_prompt, &user_prompt, seed)?;
+            match validator.validate(&sql) {
+                Ok(()) => {
+                    return Ok(ValidatedSql {
+                        sql,
+                        validated: true,
+                    })
+                }
+                Err(error) => {
+                    user_prompt = format!(
+                        "{base_user_prompt}\n\nYour previous attempt:\n{sql}\n\nfailed to \
+                         validate with the DuckDB error:\n{error}\n\nReturn corrected DuckDB SQL."
+                    );
+                    last = sql;
+                }
+            }
+        }
+        Ok(ValidatedSql {
+            sql: last,
+            validated: false,
+        })
+    }
+
+    /// Generate SQL, streaming each decoded text fragment to `sink` as it is
+    /// produced instead of blocking until the whole statement is complete.
+    /// `sink.is_cancelled()` is polled each step so the host can stop early, and
+    /// the trailing `decode_rest()` is flushed through `sink` before returning.
+    /// Returns the accumulated (dialect-repaired) SQL.
+    pub fn generate_streaming(
+        &mut self,
+        prompt: &str,
+        table_schema: &str,
+        sink: &mut dyn TokenSink,
+    ) -> Result<String, Error> {
+        let system_prompt = self.system_prompt_with_docs(prompt)?;
+        let user_prompt = format!("{}\nSCHEMA: {}", prompt, table_schema);
+        let mut logits_processor = self.logits_processor(self.config.seed);
+        self.tokenizer.clear();
+        let system_message = Message {
+            role: "system".to_string(),
+            content: system_prompt,
+        };
+        let user_message = Message {
+            role: "user".to_string(),
+            content: user_prompt,
+        };
+        let chat_template = apply_template(
+            chat_templates::ChatTemplate::ChatML,
+            &vec![system_message, user_message],
+            true,
+        )?;
+        let mut tokens = self
+            .tokenizer
+            .tokenizer()
+            .encode(chat_template, true)
+            .map_err(Error::msg)?
+            .get_ids()
+            .to_vec();
+        let mut output = String::new();
+
+        let eos_token = match self.tokenizer.get_token("<|endoftext|>") {
+            Some(token) => token,
+            None => anyhow::bail!("cannot find the <|endoftext|> token"),
+        };
+        let eos_token2 = match self.tokenizer.get_token("<|im_end|>") {
+            Some(token) => token,
+            None => anyhow::bail!("cannot find the <|im_end|> token"),
+        };
+        for index in 0..self.config.max_tokens {
+            if sink.is_cancelled() {
+                break;
+            }
+            let context_size = if index > 0 { 1 } else { tokens.len() };
+            let start_pos = tokens.len().saturating_sub(context_size);
+            let ctxt = &tokens[start_pos..];
+            let input = Tensor::new(ctxt, &self.device)?.unsqueeze(0)?;
+            let logits = self.model.forward(&input, start_pos)?;
+            let logits = logits.squeeze(0)?.squeeze(0)?.to_dtype(DType::F32)?;
+            let logits = if self.config.repeat_penalty == 1. {
+                logits
+            } else {
+                let start_at = tokens.len().saturating_sub(self.config.repeat_last_n);
+                candle_transformers::utils::apply_repeat_penalty(
+                    &logits,
+                    self.config.repeat_penalty,
+                    &tokens[start_at..],
+                )?
+            };
+
+            let next_token = logits_processor.sample(&logits)?;
+            tokens.push(next_token);
+            if next_token == eos_token || next_token == eos_token2 {
+                break;
+            }
+            if let Some(t) = self.tokenizer.next_token(next_token)? {
+                sink.on_token(&t);
+                output.push_str(&t);
+            }
+        }
+        if let Some(rest) = self.tokenizer.decode_rest().map_err(Error::msg)? {
+            sink.on_token(&rest);
+            output.push_str(&rest);
+        }
+        self.model.clear_kv_cache();
+        if self.dialect_repair_enabled {
+            output = self.dialect_rewriter.apply(&output);
+        }
         Ok(output)
     }
+
+    /// Replace the active decoding configuration. Useful to switch between the
+    /// reproducible greedy default and a sampling config for N-best generation.
+    pub fn set_generation_config(&mut self, config: GenerationConfig) {
+        self.config = config;
+    }
+
+    /// Sample `m` independent completions (seeded `config.seed + i` so each draw
+    /// differs yet the whole set stays reproducible), validate each against
+    /// `validator`, and return them ranked by mean per-token log-probability,
+    /// best first. Intended for use with a nonzero temperature; with the greedy
+    /// default every completion is identical.
+    pub fn generate_n_best(
+        &mut self,
+        prompt: &str,
+        table_schema: &str,
+        validator: &dyn SqlValidator,
+        m: usize,
+    ) -> Result<Vec<SqlCandidate>, Error> {
+        let system_prompt = self.system_prompt_with_docs(prompt)?;
+        let user_prompt = format!("{}\nSCHEMA: {}", prompt, table_schema);
+        let base_seed = self.config.seed;
+        let mut candidates = Vec::with_capacity(m.max(1));
+        for i in 0..m.max(1) {
+            let (sql, score) =
+                self.complete(&system_prompt, &user_prompt, base_seed.wrapping_add(i as u64))?;
+            let validated = validator.validate(&sql).is_ok();
+            candidates.push(SqlCandidate {
+                sql,
+                score,
+                validated,
+            });
+        }
+        candidates.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+        Ok(candidates)
+    }
+
+    /// Generate `m` candidates and return the highest-probability one that
+    /// validates against `validator`, falling back to the top-ranked candidate
+    /// if none validate.
+    pub fn generate_best(
+        &mut self,
+        prompt: &str,
+        table_schema: &str,
+        validator: &dyn SqlValidator,
+        m: usize,
+    ) -> Result<ValidatedSql, Error> {
+        let candidates = self.generate_n_best(prompt, table_schema, validator, m)?;
+        let chosen = candidates
+            .iter()
+            .find(|c| c.validated)
+            .or_else(|| candidates.first());
+        match chosen {
+            Some(candidate) => Ok(ValidatedSql {
+                sql: candidate.sql.clone(),
+                validated: candidate.validated,
+            }),
+            None => Ok(ValidatedSql {
+                sql: String::new(),
+                validated: false,
+            }),
+        }
+    }
 }